@@ -12,13 +12,42 @@ use log::info;
 use smtp_proto::{EhloResponse, EXT_START_TLS};
 use std::hash::Hash;
 use std::time::Duration;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use worker::{SecureTransport, Socket};
 
+#[cfg(not(target_family = "wasm"))]
+use tokio::time::timeout;
+#[cfg(target_family = "wasm")]
+use wasmtimer::tokio::timeout;
+
 use crate::{Credentials, SmtpClient, SmtpClientBuilder};
 
 use super::AssertReply;
 
+/// Proxy to dial before reaching the SMTP server, for environments where a
+/// direct connection to the relay is not possible.
+#[derive(Debug, Clone)]
+pub enum ProxyConfig {
+    /// SOCKS5 proxy (RFC 1928), with optional username/password auth (RFC 1929).
+    Socks5 {
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// HTTP proxy reached via the `CONNECT` method.
+    HttpConnect { host: String, port: u16 },
+}
+
+impl ProxyConfig {
+    fn addr(&self) -> (&str, u16) {
+        match self {
+            ProxyConfig::Socks5 { host, port, .. } => (host, *port),
+            ProxyConfig::HttpConnect { host, port } => (host, *port),
+        }
+    }
+}
+
 impl<T: AsRef<str> + PartialEq + Eq + Hash> SmtpClientBuilder<T> {
     pub fn new(hostname: T, port: u16, secure_transport: SecureTransport) -> Self {
         SmtpClientBuilder {
@@ -30,6 +59,7 @@ impl<T: AsRef<str> + PartialEq + Eq + Hash> SmtpClientBuilder<T> {
             local_host: String::from("[127.0.0.1]"),
             credentials: None,
             say_ehlo: true,
+            proxy: None,
         }
     }
 
@@ -63,15 +93,33 @@ impl<T: AsRef<str> + PartialEq + Eq + Hash> SmtpClientBuilder<T> {
         self
     }
 
+    /// Connects through a SOCKS5 or HTTP CONNECT proxy instead of dialing the
+    /// SMTP server directly.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
     pub async fn connect(&self) -> crate::Result<SmtpClient<Socket>> {
         info!("connecting to {}:{}", self.hostname.as_ref(), self.port);
-        let mut client = SmtpClient::connect(
-            self.hostname.as_ref(),
-            self.port,
-            self.secure_transport.clone(),
-            self.timeout,
-        )
-        .await?;
+        let mut client = if let Some(proxy) = &self.proxy {
+            SmtpClient::connect_via_proxy(
+                proxy,
+                self.hostname.as_ref(),
+                self.port,
+                self.secure_transport.clone(),
+                self.timeout,
+            )
+            .await?
+        } else {
+            SmtpClient::connect(
+                self.hostname.as_ref(),
+                self.port,
+                self.secure_transport.clone(),
+                self.timeout,
+            )
+            .await?
+        };
         info!("await completion");
         client.read().await?.assert_positive_completion()?;
         info!("awaited completion");
@@ -85,7 +133,7 @@ impl<T: AsRef<str> + PartialEq + Eq + Hash> SmtpClientBuilder<T> {
                 client.lhlo(&self.local_host).await?
             };
             if response.has_capability(EXT_START_TLS) {
-                client = client.start_tls().await?;
+                client = client.start_tls(self.hostname.as_ref()).await?;
             } else {
                 return Err(crate::Error::MissingStartTls);
             }
@@ -98,8 +146,26 @@ impl<T: AsRef<str> + PartialEq + Eq + Hash> SmtpClientBuilder<T> {
             let capabilities = client.capabilities(&self.local_host, self.is_lmtp).await?;
             // Authenticate
             if let Some(credentials) = &self.credentials {
-                client.authenticate(&credentials, &capabilities).await?;
+                match credentials {
+                    Credentials::OAuthBearer { user, token } => {
+                        client
+                            .authenticate_oauth(
+                                user.as_ref(),
+                                self.hostname.as_ref(),
+                                self.port,
+                                token.as_ref(),
+                                &capabilities,
+                            )
+                            .await?;
+                    }
+                    _ => {
+                        client.authenticate(&credentials, &capabilities).await?;
+                    }
+                }
             }
+            // Cache it so callers can check `SmtpClient::supports_chunking()`
+            // without re-issuing EHLO/LHLO over the live connection.
+            client.ehlo_capabilities = Some(capabilities);
         }
 
         Ok(client)
@@ -133,8 +199,65 @@ impl SmtpClient<Socket> {
         Self::new(socket, timeout).await
     }
 
-    pub async fn start_tls(self) -> crate::Result<Self> {
-        Self::new(self.stream.start_tls(), self.timeout).await
+    /// Upgrades the connection to TLS, verifying the server's certificate
+    /// against `hostname`.
+    ///
+    /// `hostname` must be the real SMTP relay's name, not whatever host the
+    /// underlying socket happened to dial — for a direct connection those are
+    /// the same, but for a tunnel opened through [`ProxyConfig`] the socket was
+    /// dialed against the *proxy*, so the proxy's name must never be used here.
+    pub async fn start_tls(self, hostname: &str) -> crate::Result<Self> {
+        Self::new(self.stream.start_tls(hostname), self.timeout).await
+    }
+
+    /// Dials `proxy` and tunnels a connection to `target_host:target_port`
+    /// through it, before handing the tunneled stream off to the usual
+    /// TLS/STARTTLS logic.
+    async fn connect_via_proxy(
+        proxy: &ProxyConfig,
+        target_host: &str,
+        target_port: u16,
+        secure_transport: SecureTransport,
+        conn_timeout: Duration,
+    ) -> crate::Result<Self> {
+        let (proxy_host, proxy_port) = proxy.addr();
+        let mut client =
+            Self::connect(proxy_host, proxy_port, SecureTransport::Plain, conn_timeout).await?;
+
+        // A stalled or malicious proxy must not be able to hang `connect()`
+        // forever: the handshake gets the same timeout budget as any other
+        // command on this connection.
+        timeout(conn_timeout, async {
+            match proxy {
+                ProxyConfig::Socks5 {
+                    username, password, ..
+                } => {
+                    socks5_connect(
+                        &mut client.stream,
+                        username.as_deref(),
+                        password.as_deref(),
+                        target_host,
+                        target_port,
+                    )
+                    .await
+                }
+                ProxyConfig::HttpConnect { .. } => {
+                    http_connect(&mut client.stream, target_host, target_port).await
+                }
+            }
+        })
+        .await
+        .map_err(|_| crate::Error::Timeout)??;
+
+        if secure_transport == SecureTransport::Tls {
+            // The socket was dialed against the proxy, so it only knows the
+            // proxy's name; verify against `target_host` (the relay reached
+            // through the tunnel) instead, or the TLS handshake would silently
+            // check the wrong certificate.
+            client = client.start_tls(target_host).await?;
+        }
+
+        Ok(client)
     }
 
     async fn new(socket: Socket, timeout: Duration) -> crate::Result<Self> {
@@ -142,6 +265,306 @@ impl SmtpClient<Socket> {
         Ok(Self {
             stream: socket,
             timeout,
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
         })
     }
 }
+
+/// Performs the SOCKS5 (RFC 1928) greeting, optional username/password
+/// authentication (RFC 1929), and `CONNECT` request needed to open a tunnel
+/// to `target_host:target_port` through a SOCKS5 proxy.
+async fn socks5_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    username: Option<&str>,
+    password: Option<&str>,
+    target_host: &str,
+    target_port: u16,
+) -> crate::Result<()> {
+    let use_auth = username.is_some() && password.is_some();
+    let methods: &[u8] = if use_auth { &[0x00, 0x02] } else { &[0x00] };
+
+    let mut greeting = vec![0x05, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+    stream.flush().await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 {
+        return Err(crate::Error::UnparseableReply);
+    }
+
+    match reply[1] {
+        0x00 => {}
+        0x02 if use_auth => {
+            let username = username.unwrap_or_default();
+            let password = password.unwrap_or_default();
+            let username_len: u8 = username
+                .len()
+                .try_into()
+                .map_err(|_| crate::Error::InvalidParameter)?;
+            let password_len: u8 = password
+                .len()
+                .try_into()
+                .map_err(|_| crate::Error::InvalidParameter)?;
+            let mut auth = vec![0x01, username_len];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password_len);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await?;
+            stream.flush().await?;
+
+            let mut auth_reply = [0u8; 2];
+            stream.read_exact(&mut auth_reply).await?;
+            if auth_reply[1] != 0x00 {
+                return Err(crate::Error::UnparseableReply);
+            }
+        }
+        _ => return Err(crate::Error::UnparseableReply),
+    }
+
+    let target_host_len: u8 = target_host
+        .len()
+        .try_into()
+        .map_err(|_| crate::Error::InvalidParameter)?;
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, target_host_len];
+    request.extend_from_slice(target_host.as_bytes());
+    request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0x00 {
+        return Err(crate::Error::UnparseableReply);
+    }
+
+    // Drain the bound address returned with the reply; its length depends on
+    // the address type (ATYP) the proxy chose to respond with.
+    let addr_len = match header[3] {
+        0x01 => 4,                                         // IPv4
+        0x04 => 16,                                         // IPv6
+        0x03 => stream.read_u8().await? as usize,           // domain name
+        _ => return Err(crate::Error::UnparseableReply),
+    };
+    let mut addr_and_port = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut addr_and_port).await?;
+
+    Ok(())
+}
+
+/// Issues an HTTP `CONNECT` request and parses the response status line,
+/// opening a tunnel to `target_host:target_port` through an HTTP proxy.
+async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target_host: &str,
+    target_port: u16,
+) -> crate::Result<()> {
+    let request = format!(
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n",
+        host = target_host,
+        port = target_port
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await?;
+        response.push(byte[0]);
+    }
+
+    let status_line = response
+        .split(|&b| b == b'\n')
+        .next()
+        .ok_or(crate::Error::UnparseableReply)?;
+    let status = std::str::from_utf8(status_line)
+        .ok()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(crate::Error::UnparseableReply)?;
+
+    if status == 200 {
+        Ok(())
+    } else {
+        Err(crate::Error::UnparseableReply)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    use super::{http_connect, socks5_connect};
+
+    #[derive(Default)]
+    struct AsyncDuplexMock {
+        write_buf: Vec<u8>,
+        read_buf: Vec<u8>,
+        read_pos: usize,
+    }
+
+    impl AsyncRead for AsyncDuplexMock {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let remaining = &self.read_buf[self.read_pos..];
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            self.read_pos += n;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for AsyncDuplexMock {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            self.write_buf.extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_no_auth() {
+        let mut stream = AsyncDuplexMock {
+            // method selection: version 5, no auth required
+            // CONNECT reply: version 5, success, reserved, IPv4, addr, port
+            read_buf: vec![0x05, 0x00, 0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0],
+            ..Default::default()
+        };
+
+        socks5_connect(&mut stream, None, None, "smtp.example.com", 587)
+            .await
+            .unwrap();
+
+        let mut expected = vec![0x05, 0x01, 0x00];
+        expected.extend_from_slice(&[0x05, 0x01, 0x00, 0x03, b"smtp.example.com".len() as u8]);
+        expected.extend_from_slice(b"smtp.example.com");
+        expected.extend_from_slice(&587u16.to_be_bytes());
+        assert_eq!(stream.write_buf, expected);
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_with_auth() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: vec![
+                0x05, 0x02, // greeting reply: username/password required
+                0x01, 0x00, // auth reply: success
+                0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0, // CONNECT reply
+            ],
+            ..Default::default()
+        };
+
+        socks5_connect(
+            &mut stream,
+            Some("alice"),
+            Some("hunter2"),
+            "smtp.example.com",
+            587,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_rejects_failure_reply() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: vec![0x05, 0x00, 0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0],
+            ..Default::default()
+        };
+
+        assert!(socks5_connect(&mut stream, None, None, "smtp.example.com", 587)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_rejects_over_long_hostname() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: vec![0x05, 0x00],
+            ..Default::default()
+        };
+        let target_host = "a".repeat(256);
+
+        assert!(
+            socks5_connect(&mut stream, None, None, &target_host, 587)
+                .await
+                .is_err()
+        );
+        // The oversized hostname must be rejected before the CONNECT request
+        // is written (only the greeting goes out) — not truncated into a
+        // corrupt length-prefixed field.
+        assert_eq!(stream.write_buf, vec![0x05, 0x01, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_rejects_over_long_credentials() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: vec![0x05, 0x02],
+            ..Default::default()
+        };
+        let username = "a".repeat(256);
+
+        assert!(socks5_connect(
+            &mut stream,
+            Some(&username),
+            Some("hunter2"),
+            "smtp.example.com",
+            587,
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn http_connect_accepts_200() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: b"HTTP/1.1 200 Connection Established\r\n\r\n".to_vec(),
+            ..Default::default()
+        };
+
+        http_connect(&mut stream, "smtp.example.com", 587)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(stream.write_buf).unwrap(),
+            "CONNECT smtp.example.com:587 HTTP/1.1\r\nHost: smtp.example.com:587\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_connect_rejects_non_200() {
+        let mut stream = AsyncDuplexMock {
+            read_buf: b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec(),
+            ..Default::default()
+        };
+
+        assert!(http_connect(&mut stream, "smtp.example.com", 587)
+            .await
+            .is_err());
+    }
+}
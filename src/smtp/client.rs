@@ -8,7 +8,11 @@
  * except according to those terms.
  */
 
-use smtp_proto::{response::parser::ResponseReceiver, Response};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use smtp_proto::{
+    response::parser::ResponseReceiver, EhloResponse, Response, AUTH_OAUTHBEARER, AUTH_XOAUTH2,
+    EXT_CHUNKING, EXT_DSN,
+};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[cfg(not(target_family = "wasm"))]
@@ -18,67 +22,151 @@ use wasmtimer::tokio::timeout;
 
 use crate::SmtpClient;
 
+/// Amount of additional capacity reserved in the read buffer on every
+/// refill from the socket.
+const READ_CHUNK_SIZE: usize = 1024;
+
+/// `RET` parameter for a DSN-enabled `MAIL FROM` (RFC 3461).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsnRet {
+    /// Request the full message be returned with the notification.
+    Full,
+    /// Request only the message headers be returned with the notification.
+    Hdrs,
+}
+
+/// `NOTIFY` conditions for a DSN-enabled `RCPT TO` (RFC 3461).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsnNotify {
+    Success,
+    Failure,
+    Delay,
+}
+
+impl DsnNotify {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DsnNotify::Success => "SUCCESS",
+            DsnNotify::Failure => "FAILURE",
+            DsnNotify::Delay => "DELAY",
+        }
+    }
+}
+
+/// Delivery Status Notification parameters to attach to `MAIL FROM`.
+#[derive(Debug, Clone, Default)]
+pub struct MailFromDsn<'x> {
+    pub ret: Option<DsnRet>,
+    pub envid: Option<&'x str>,
+}
+
+/// Delivery Status Notification parameters to attach to `RCPT TO`.
+#[derive(Debug, Clone, Default)]
+pub struct RcptToDsn<'x> {
+    pub notify: &'x [DsnNotify],
+    pub orcpt: Option<&'x str>,
+}
+
+/// Rejects values containing a bare CR or LF before they're spliced into a
+/// command line, so a caller passing through an unsanitized address, ENVID,
+/// or ORCPT can't smuggle extra SMTP commands into the session.
+fn reject_crlf(value: &str) -> crate::Result<&str> {
+    if value.bytes().any(|b| b == b'\r' || b == b'\n') {
+        Err(crate::Error::InvalidParameter)
+    } else {
+        Ok(value)
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite + Unpin> SmtpClient<T> {
+    /// Reads one reply out of the (possibly already buffered) stream.
+    ///
+    /// Bytes beyond the parsed reply — e.g. a pipelined banner or the start
+    /// of the next reply — are kept in `self.buf` rather than discarded, so
+    /// a subsequent `read`/`read_many` call picks up exactly where this one
+    /// left off instead of losing data already in flight.
     pub async fn read(&mut self) -> crate::Result<Response<String>> {
-        let mut buf = vec![0u8; 1024];
         let mut parser = ResponseReceiver::default();
 
         loop {
-            let br = self.stream.read(&mut buf).await?;
-
-            if br > 0 {
-                match parser.parse(&mut buf[..br].iter()) {
-                    Ok(reply) => return Ok(reply),
-                    Err(err) => match err {
-                        smtp_proto::Error::NeedsMoreData { .. } => (),
-                        _ => {
-                            return Err(crate::Error::UnparseableReply);
-                        }
-                    },
+            {
+                let mut iter = self.buf[self.buf_pos..self.buf_len].iter();
+                let remaining = iter.len();
+
+                match parser.parse(&mut iter) {
+                    Ok(reply) => {
+                        self.buf_pos += remaining - iter.len();
+                        return Ok(reply);
+                    }
+                    Err(smtp_proto::Error::NeedsMoreData { .. }) => {
+                        // The parser consumed everything currently buffered
+                        // (and asked for more), so advance past it — `fill_buf`
+                        // only appends, and re-parsing these bytes alongside
+                        // the newly appended tail would feed them to `parser`
+                        // twice.
+                        self.buf_pos += remaining - iter.len();
+                    }
+                    Err(_) => return Err(crate::Error::UnparseableReply),
                 }
-            } else {
-                return Err(crate::Error::UnparseableReply);
             }
+
+            self.fill_buf().await?;
         }
     }
 
     pub async fn read_many(&mut self, num: usize) -> crate::Result<Vec<Response<String>>> {
-        let mut buf = vec![0u8; 1024];
         let mut response = Vec::with_capacity(num);
         let mut parser = ResponseReceiver::default();
 
-        'outer: loop {
-            let br = self.stream.read(&mut buf).await?;
-
-            if br > 0 {
-                let mut iter = buf[..br].iter();
-
-                loop {
-                    match parser.parse(&mut iter) {
-                        Ok(reply) => {
-                            response.push(reply);
-                            if response.len() != num {
-                                parser.reset();
-                            } else {
-                                break 'outer;
-                            }
-                        }
-                        Err(err) => match err {
-                            smtp_proto::Error::NeedsMoreData { .. } => break,
-                            _ => {
-                                return Err(crate::Error::UnparseableReply);
-                            }
-                        },
+        while response.len() < num {
+            let mut iter = self.buf[self.buf_pos..self.buf_len].iter();
+            let remaining = iter.len();
+
+            match parser.parse(&mut iter) {
+                Ok(reply) => {
+                    self.buf_pos += remaining - iter.len();
+                    response.push(reply);
+                    if response.len() != num {
+                        parser.reset();
                     }
                 }
-            } else {
-                return Err(crate::Error::UnparseableReply);
+                Err(smtp_proto::Error::NeedsMoreData { .. }) => {
+                    self.buf_pos += remaining - iter.len();
+                    self.fill_buf().await?;
+                }
+                Err(_) => return Err(crate::Error::UnparseableReply),
             }
         }
 
         Ok(response)
     }
 
+    /// Tops up `self.buf` with more bytes from the socket, compacting away
+    /// already-consumed bytes first so the buffer doesn't grow without bound
+    /// across the lifetime of the connection.
+    async fn fill_buf(&mut self) -> crate::Result<()> {
+        if self.buf_pos > 0 {
+            self.buf.copy_within(self.buf_pos..self.buf_len, 0);
+            self.buf_len -= self.buf_pos;
+            self.buf_pos = 0;
+        }
+
+        if self.buf.len() < self.buf_len + READ_CHUNK_SIZE {
+            self.buf.resize(self.buf_len + READ_CHUNK_SIZE, 0);
+        }
+
+        let br = self
+            .stream
+            .read(&mut self.buf[self.buf_len..self.buf_len + READ_CHUNK_SIZE])
+            .await?;
+        if br == 0 {
+            return Err(crate::Error::UnparseableReply);
+        }
+        self.buf_len += br;
+
+        Ok(())
+    }
+
     /// Sends a command to the SMTP server and waits for a reply.
     pub async fn cmd(&mut self, cmd: impl AsRef<[u8]>) -> crate::Result<Response<String>> {
         timeout(self.timeout, async {
@@ -107,16 +195,245 @@ impl<T: AsyncRead + AsyncWrite + Unpin> SmtpClient<T> {
         .await
         .map_err(|_| crate::Error::Timeout)?
     }
+
+    /// Sends `MAIL FROM`, appending RFC 3461 DSN parameters (`RET`, `ENVID`)
+    /// when `dsn` is set and the server's EHLO response advertised the `DSN`
+    /// capability. The parameters are silently omitted otherwise, since most
+    /// servers reject `MAIL FROM` commands carrying extensions they didn't
+    /// advertise.
+    pub async fn mail_from(
+        &mut self,
+        sender: &str,
+        dsn: Option<&MailFromDsn<'_>>,
+        capabilities: &EhloResponse<String>,
+    ) -> crate::Result<Response<String>> {
+        let mut cmd = format!("MAIL FROM:<{}>", reject_crlf(sender)?);
+
+        if capabilities.has_capability(EXT_DSN) {
+            if let Some(dsn) = dsn {
+                match dsn.ret {
+                    Some(DsnRet::Full) => cmd.push_str(" RET=FULL"),
+                    Some(DsnRet::Hdrs) => cmd.push_str(" RET=HDRS"),
+                    None => (),
+                }
+                if let Some(envid) = dsn.envid {
+                    cmd.push_str(" ENVID=");
+                    cmd.push_str(reject_crlf(envid)?);
+                }
+            }
+        }
+
+        cmd.push_str("\r\n");
+        self.cmd(cmd).await
+    }
+
+    /// Sends `RCPT TO`, appending RFC 3461 DSN parameters (`NOTIFY`, `ORCPT`)
+    /// when `dsn` is set and the server's EHLO response advertised the `DSN`
+    /// capability. The parameters are silently omitted otherwise, since most
+    /// servers reject `RCPT TO` commands carrying extensions they didn't
+    /// advertise.
+    pub async fn rcpt_to(
+        &mut self,
+        recipient: &str,
+        dsn: Option<&RcptToDsn<'_>>,
+        capabilities: &EhloResponse<String>,
+    ) -> crate::Result<Response<String>> {
+        let mut cmd = format!("RCPT TO:<{}>", reject_crlf(recipient)?);
+
+        if capabilities.has_capability(EXT_DSN) {
+            if let Some(dsn) = dsn {
+                if !dsn.notify.is_empty() {
+                    cmd.push_str(" NOTIFY=");
+                    cmd.push_str(
+                        &dsn.notify
+                            .iter()
+                            .map(DsnNotify::as_str)
+                            .collect::<Vec<_>>()
+                            .join(","),
+                    );
+                }
+                if let Some(orcpt) = dsn.orcpt {
+                    cmd.push_str(" ORCPT=rfc822;");
+                    cmd.push_str(reject_crlf(orcpt)?);
+                }
+            }
+        }
+
+        cmd.push_str("\r\n");
+        self.cmd(cmd).await
+    }
+
+    /// Authenticates with `Credentials::OAuthBearer { user, token }`, picking
+    /// whichever OAuth 2.0 bearer-token mechanism the server's EHLO `AUTH`
+    /// capability list advertises — preferring the standardized `OAUTHBEARER`
+    /// (RFC 7628) over the older, Google-specific `XOAUTH2` when the server
+    /// offers both. `SmtpClientBuilder::connect()` calls this directly for
+    /// that credentials variant rather than through `authenticate()`, since
+    /// `OAUTHBEARER`'s GS2 header needs the relay's `host`/`port`, which
+    /// `authenticate()`'s signature has no room for.
+    pub async fn authenticate_oauth(
+        &mut self,
+        user: &str,
+        host: &str,
+        port: u16,
+        token: &str,
+        capabilities: &EhloResponse<String>,
+    ) -> crate::Result<Response<String>> {
+        if capabilities.has_auth_mechanism(AUTH_OAUTHBEARER) {
+            self.authenticate_oauthbearer(user, host, port, token).await
+        } else if capabilities.has_auth_mechanism(AUTH_XOAUTH2) {
+            self.authenticate_xoauth2(user, token).await
+        } else {
+            Err(crate::Error::MissingAuthMechanism)
+        }
+    }
+
+    /// Authenticates using the `XOAUTH2` SASL mechanism, as deprecated-basic-auth
+    /// replacements such as Gmail and Office365 relays require.
+    ///
+    /// `user` and `token` are combined into the `user=<user>^Aauth=Bearer
+    /// <token>^A^A` initial response and base64-encoded, per Google's XOAUTH2
+    /// spec. Only use this when the EHLO `AUTH` capability list includes
+    /// `XOAUTH2`.
+    pub async fn authenticate_xoauth2(
+        &mut self,
+        user: &str,
+        token: &str,
+    ) -> crate::Result<Response<String>> {
+        let initial_response = STANDARD.encode(format!(
+            "user={}\x01auth=Bearer {}\x01\x01",
+            user, token
+        ));
+        self.auth_with_continuation("XOAUTH2", &initial_response)
+            .await
+    }
+
+    /// Authenticates using the `OAUTHBEARER` SASL mechanism (RFC 7628).
+    ///
+    /// Only use this when the EHLO `AUTH` capability list includes
+    /// `OAUTHBEARER`.
+    pub async fn authenticate_oauthbearer(
+        &mut self,
+        user: &str,
+        host: &str,
+        port: u16,
+        token: &str,
+    ) -> crate::Result<Response<String>> {
+        let initial_response = STANDARD.encode(format!(
+            "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+            user, host, port, token
+        ));
+        self.auth_with_continuation("OAUTHBEARER", &initial_response)
+            .await
+    }
+
+    /// Sends `AUTH <mechanism> <initial_response>` and, if the server rejects
+    /// the token with a base64-encoded error challenge (a `334` continuation)
+    /// rather than a final reply, sends an empty continuation so the exchange
+    /// fails cleanly instead of hanging.
+    async fn auth_with_continuation(
+        &mut self,
+        mechanism: &str,
+        initial_response: &str,
+    ) -> crate::Result<Response<String>> {
+        let response = self
+            .cmd(format!("AUTH {} {}\r\n", mechanism, initial_response))
+            .await?;
+        if response.code == 334 {
+            self.cmd(b"\r\n".to_vec()).await
+        } else {
+            Ok(response)
+        }
+    }
+
+    /// Sends a message body and collects the end-of-`DATA` reply(ies).
+    ///
+    /// Plain SMTP produces a single reply after the final `.`. LMTP (RFC 2033)
+    /// differs: the server instead sends one reply per recipient that was
+    /// accepted during the `RCPT` phase, in the order they were issued. Pass
+    /// `accepted_rcpts` as the addresses of the recipients that were accepted,
+    /// in that order (a single entry for SMTP); one reply is read per address
+    /// and each reply is returned paired with its address, so partial-success
+    /// delivery can be observed, e.g. recipient A accepted with a `250` while
+    /// recipient B is rejected with a `550`.
+    pub async fn send_data_lmtp<'x>(
+        &mut self,
+        message: &[u8],
+        accepted_rcpts: impl IntoIterator<Item = &'x str>,
+    ) -> crate::Result<Vec<(&'x str, Response<String>)>> {
+        self.write_message(message).await?;
+        let accepted_rcpts: Vec<&str> = accepted_rcpts.into_iter().collect();
+        let responses = self.read_many(accepted_rcpts.len()).await?;
+        Ok(accepted_rcpts.into_iter().zip(responses).collect())
+    }
+
+    /// Returns whether the server advertised the `CHUNKING` extension (RFC
+    /// 3030) in the EHLO/LHLO response [`SmtpClientBuilder::connect`] cached
+    /// at connect time, i.e. whether [`SmtpClient::write_message_bdat`] may be
+    /// used in place of [`SmtpClient::write_message`]. Returns `false` if the
+    /// client wasn't built with `say_ehlo(true)` (the default).
+    pub fn supports_chunking(&self) -> bool {
+        self.ehlo_capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.has_capability(EXT_CHUNKING))
+    }
+
+    /// Sends a message using the `BDAT` command (RFC 3030 `CHUNKING` extension)
+    /// instead of `DATA`.
+    ///
+    /// Unlike [`SmtpClient::write_message`], `BDAT` carries an explicit byte count
+    /// for every chunk, so no dot-stuffing or CRLF scanning is needed and 8-bit or
+    /// binary bodies pass through untouched. The message is split into chunks of
+    /// at most `chunk_size` bytes, each sent as a `BDAT <size>\r\n` header followed
+    /// by the raw chunk bytes, with the last chunk marked `BDAT <size> LAST\r\n`.
+    /// The server replies to every `BDAT` command, not just the final one, so one
+    /// reply is read per chunk sent and returned in order — the last entry is the
+    /// delivery disposition for the message as a whole. Callers should only take
+    /// this path when [`SmtpClient::supports_chunking`] returns `true`.
+    pub async fn write_message_bdat(
+        &mut self,
+        message: &[u8],
+        chunk_size: usize,
+    ) -> crate::Result<Vec<Response<String>>> {
+        timeout(self.timeout, async {
+            let mut num_chunks = 0;
+
+            if message.is_empty() {
+                self.stream.write_all(b"BDAT 0 LAST\r\n").await?;
+                num_chunks = 1;
+            } else {
+                let mut chunks = message.chunks(chunk_size.max(1)).peekable();
+                while let Some(chunk) = chunks.next() {
+                    let header = if chunks.peek().is_some() {
+                        format!("BDAT {}\r\n", chunk.len())
+                    } else {
+                        format!("BDAT {} LAST\r\n", chunk.len())
+                    };
+                    self.stream.write_all(header.as_bytes()).await?;
+                    self.stream.write_all(chunk).await?;
+                    num_chunks += 1;
+                }
+            }
+
+            self.stream.flush().await?;
+            self.read_many(num_chunks).await
+        })
+        .await
+        .map_err(|_| crate::Error::Timeout)?
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::time::Duration;
 
+    use smtp_proto::{EhloResponse, EXT_CHUNKING, EXT_DSN};
     use tokio::io::{AsyncRead, AsyncWrite};
 
     use crate::SmtpClient;
 
+    use super::{DsnNotify, DsnRet, Engine, MailFromDsn, RcptToDsn, STANDARD};
+
     #[derive(Default)]
     struct AsyncBufWriter {
         buf: Vec<u8>,
@@ -157,6 +474,489 @@ mod test {
         }
     }
 
+    #[derive(Default)]
+    struct AsyncDuplexMock {
+        write_buf: Vec<u8>,
+        read_buf: Vec<u8>,
+        read_pos: usize,
+    }
+
+    impl AsyncRead for AsyncDuplexMock {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let remaining = &self.read_buf[self.read_pos..];
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            self.read_pos += n;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for AsyncDuplexMock {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            self.write_buf.extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn write_message_bdat_chunks_and_marks_last() {
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"250 2.1.0 Continue\r\n250 2.1.0 Continue\r\n250 2.1.0 Continue\r\n250 2.0.0 OK\r\n".to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let replies = client
+            .write_message_bdat(b"Hello world, this is a test message", 10)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(client.stream.write_buf).unwrap(),
+            concat!(
+                "BDAT 10\r\n",
+                "Hello worl",
+                "BDAT 10\r\n",
+                "d, this is",
+                "BDAT 10\r\n",
+                " a test me",
+                "BDAT 5 LAST\r\n",
+                "ssage",
+            )
+        );
+        // One reply per BDAT chunk sent, in order — not just the final one.
+        assert_eq!(replies.len(), 4);
+        assert!(replies[..3].iter().all(|r| r.code == 250));
+        assert_eq!(replies[3].code, 250);
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn read_retains_pipelined_bytes_across_calls() {
+        // Two replies arrive in the same socket read, e.g. a pipelined greeting
+        // and capability banner. The first `read` call must not discard the
+        // second reply already sitting in the buffer.
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"220 mx.example.com ESMTP\r\n250-mx.example.com\r\n250 PIPELINING\r\n"
+                    .to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let first = client.read().await.unwrap();
+        assert_eq!(first.code, 220);
+        // No further bytes should have been read from the socket: both replies
+        // were already sitting in the buffered chunk.
+        assert_eq!(client.stream.read_pos, client.stream.read_buf.len());
+
+        let second = client.read().await.unwrap();
+        assert_eq!(second.code, 250);
+    }
+
+    #[derive(Default)]
+    struct ChunkedReader {
+        chunks: std::collections::VecDeque<Vec<u8>>,
+    }
+
+    impl AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if let Some(chunk) = self.chunks.pop_front() {
+                buf.put_slice(&chunk);
+            }
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for ChunkedReader {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn read_handles_reply_split_across_multiple_socket_reads() {
+        // The EHLO capability banner arrives in three separate TCP reads, as is
+        // routine against real servers. Each `fill_buf()` call must only hand
+        // the parser the newly arrived tail, never bytes it already consumed.
+        let mut client = SmtpClient {
+            stream: ChunkedReader {
+                chunks: [
+                    b"250-mx.example.com at your serv".to_vec(),
+                    b"ice\r\n250-PIPELINING\r\n250 8BITM".to_vec(),
+                    b"IME\r\n".to_vec(),
+                ]
+                .into(),
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        // The `250-` continuation lines make the banner a single reply.
+        let first = client.read_many(1).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].code, 250);
+        // All three chunks were consumed, and nothing is left over in the buffer.
+        assert!(client.stream.chunks.is_empty());
+        assert_eq!(client.buf_pos, client.buf_len);
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn authenticate_xoauth2_sends_base64_initial_response() {
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"235 2.7.0 Authentication successful\r\n".to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let response = client
+            .authenticate_xoauth2("user@example.com", "ya29.mytoken")
+            .await
+            .unwrap();
+        assert_eq!(response.code, 235);
+
+        let expected_initial =
+            STANDARD.encode("user=user@example.com\x01auth=Bearer ya29.mytoken\x01\x01");
+        assert_eq!(
+            String::from_utf8(client.stream.write_buf).unwrap(),
+            format!("AUTH XOAUTH2 {}\r\n", expected_initial)
+        );
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn authenticate_oauthbearer_sends_base64_initial_response() {
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"235 2.7.0 Authentication successful\r\n".to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let response = client
+            .authenticate_oauthbearer("user@example.com", "smtp.example.com", 587, "mytoken")
+            .await
+            .unwrap();
+        assert_eq!(response.code, 235);
+
+        let expected_initial = STANDARD.encode(
+            "n,a=user@example.com,\x01host=smtp.example.com\x01port=587\x01auth=Bearer mytoken\x01\x01",
+        );
+        assert_eq!(
+            String::from_utf8(client.stream.write_buf).unwrap(),
+            format!("AUTH OAUTHBEARER {}\r\n", expected_initial)
+        );
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn oauth_error_challenge_gets_empty_continuation() {
+        // A rejected token gets a `334`-prefixed, base64-encoded JSON error
+        // challenge instead of a final reply; the client must answer with an
+        // empty continuation rather than hang the AUTH exchange.
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: [
+                    b"334 eyJzdGF0dXMiOiI0MDEifQ==\r\n".as_slice(),
+                    b"535 5.7.9 Authentication failed\r\n".as_slice(),
+                ]
+                .concat(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let response = client
+            .authenticate_xoauth2("user@example.com", "bad-token")
+            .await
+            .unwrap();
+        assert_eq!(response.code, 535);
+        assert!(String::from_utf8(client.stream.write_buf)
+            .unwrap()
+            .ends_with("AUTH XOAUTH2 dXNlcj11c2VyQGV4YW1wbGUuY29tAWF1dGg9QmVhcmVyIGJhZC10b2tlbgEB\r\n\r\n"));
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn mail_from_and_rcpt_to_append_dsn_params_when_advertised() {
+        let capabilities = EhloResponse {
+            capabilities: EXT_DSN,
+            ..Default::default()
+        };
+
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"250 2.1.0 Sender OK\r\n250 2.1.5 Recipient OK\r\n".to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        client
+            .mail_from(
+                "sender@example.com",
+                Some(&MailFromDsn {
+                    ret: Some(DsnRet::Full),
+                    envid: Some("abc123"),
+                }),
+                &capabilities,
+            )
+            .await
+            .unwrap();
+        client
+            .rcpt_to(
+                "recipient@example.com",
+                Some(&RcptToDsn {
+                    notify: &[DsnNotify::Success, DsnNotify::Failure],
+                    orcpt: Some("recipient@example.com"),
+                }),
+                &capabilities,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(client.stream.write_buf).unwrap(),
+            concat!(
+                "MAIL FROM:<sender@example.com> RET=FULL ENVID=abc123\r\n",
+                "RCPT TO:<recipient@example.com> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;recipient@example.com\r\n",
+            )
+        );
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn mail_from_and_rcpt_to_omit_dsn_params_when_not_advertised() {
+        let capabilities = EhloResponse::<String>::default();
+
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"250 2.1.0 Sender OK\r\n250 2.1.5 Recipient OK\r\n".to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        client
+            .mail_from(
+                "sender@example.com",
+                Some(&MailFromDsn {
+                    ret: Some(DsnRet::Full),
+                    envid: Some("abc123"),
+                }),
+                &capabilities,
+            )
+            .await
+            .unwrap();
+        client
+            .rcpt_to(
+                "recipient@example.com",
+                Some(&RcptToDsn {
+                    notify: &[DsnNotify::Success],
+                    orcpt: Some("recipient@example.com"),
+                }),
+                &capabilities,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(client.stream.write_buf).unwrap(),
+            concat!(
+                "MAIL FROM:<sender@example.com>\r\n",
+                "RCPT TO:<recipient@example.com>\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn supports_chunking_reflects_cached_ehlo_capabilities() {
+        let client = SmtpClient {
+            stream: AsyncDuplexMock::default(),
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+        assert!(!client.supports_chunking());
+
+        let client = SmtpClient {
+            stream: AsyncDuplexMock::default(),
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: Some(EhloResponse {
+                capabilities: EXT_CHUNKING,
+                ..Default::default()
+            }),
+        };
+        assert!(client.supports_chunking());
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn mail_from_and_rcpt_to_reject_crlf_injection() {
+        let capabilities = EhloResponse {
+            capabilities: EXT_DSN,
+            ..Default::default()
+        };
+
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock::default(),
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+        assert!(client
+            .mail_from("sender@example.com>\r\nRCPT TO:<admin", None, &capabilities)
+            .await
+            .is_err());
+        assert!(client
+            .mail_from(
+                "sender@example.com",
+                Some(&MailFromDsn {
+                    ret: None,
+                    envid: Some("abc\r\nDATA"),
+                }),
+                &capabilities,
+            )
+            .await
+            .is_err());
+        assert!(client
+            .rcpt_to("recipient@example.com>\r\nMAIL FROM:<admin", None, &capabilities)
+            .await
+            .is_err());
+        assert!(client
+            .rcpt_to(
+                "recipient@example.com",
+                Some(&RcptToDsn {
+                    notify: &[],
+                    orcpt: Some("a\r\nDATA"),
+                }),
+                &capabilities,
+            )
+            .await
+            .is_err());
+        // None of the rejected calls should have written anything to the wire.
+        assert!(client.stream.write_buf.is_empty());
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn send_data_lmtp_zips_one_reply_per_accepted_recipient() {
+        let mut client = SmtpClient {
+            stream: AsyncDuplexMock {
+                read_buf: b"250 2.1.5 a@example.com delivered\r\n550 5.1.1 b@example.com unknown\r\n"
+                    .to_vec(),
+                ..Default::default()
+            },
+            timeout: Duration::from_secs(30),
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            ehlo_capabilities: None,
+        };
+
+        let results = client
+            .send_data_lmtp(b"Subject: test\r\n\r\nbody\r\n", ["a@example.com", "b@example.com"])
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "a@example.com");
+        assert_eq!(results[0].1.code, 250);
+        assert_eq!(results[1].0, "b@example.com");
+        assert_eq!(results[1].1.code, 550);
+    }
+
     #[cfg_attr(not(target_family = "wasm"), tokio::test)]
     #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
     async fn transparency_procedure() {
@@ -226,6 +1026,10 @@ This is a smuggled message
             let mut client = SmtpClient {
                 stream: AsyncBufWriter::default(),
                 timeout: Duration::from_secs(30),
+                buf: Vec::new(),
+                buf_pos: 0,
+                buf_len: 0,
+                ehlo_capabilities: None,
             };
             client.write_message(test.as_bytes()).await.unwrap();
             assert_eq!(String::from_utf8(client.stream.buf).unwrap(), result);